@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// This file is part of Static Web Server.
+// See https://static-web-server.net/ for more information
+// Copyright (C) 2019-present Jose Quintana <joseluisq.net>
+
+//! It provides conditional request handling (`If-Modified-Since`, `If-Unmodified-Since`,
+//! `If-None-Match`, `If-Match` and `If-Range`).
+//!
+
+use headers::{
+    ETag, HeaderMap, HeaderMapExt, IfMatch, IfModifiedSince, IfNoneMatch, IfRange,
+    IfUnmodifiedSince, LastModified, Range,
+};
+use hyper::{Body, Response, StatusCode};
+use std::time::SystemTime;
+
+/// Outcome of validating a request's conditional headers against a cached resource.
+pub(crate) enum ConditionalBody {
+    /// No body should be sent, just the given response (e.g. `304`, `412`).
+    NoBody(Response<Body>),
+    /// A body should be sent, honoring the given `Range` header if any.
+    WithBody(Option<Range>),
+}
+
+/// Parsed conditional request headers for a single request.
+pub(crate) struct ConditionalHeaders {
+    if_modified_since: Option<IfModifiedSince>,
+    if_unmodified_since: Option<IfUnmodifiedSince>,
+    if_none_match: Option<IfNoneMatch>,
+    if_match: Option<IfMatch>,
+    if_range: Option<IfRange>,
+    range: Option<Range>,
+}
+
+impl ConditionalHeaders {
+    pub(crate) fn new(headers: &HeaderMap) -> Self {
+        Self {
+            if_modified_since: headers.typed_get(),
+            if_unmodified_since: headers.typed_get(),
+            if_none_match: headers.typed_get(),
+            if_match: headers.typed_get(),
+            if_range: headers.typed_get(),
+            range: headers.typed_get(),
+        }
+    }
+
+    /// Validates the parsed headers against a resource's `last_modified` time, its weak
+    /// `etag` (used for `If-None-Match`/`If-Range`), and its `strong_etag` (used for
+    /// `If-Match`, which RFC 7232 requires strong comparison for), returning whether a
+    /// body should be sent and, if so, which `Range` to honor.
+    ///
+    /// `strong_etag` is a closure rather than a plain value because deriving it is a
+    /// content hash of the whole resource: it's only worth paying for on the (rare)
+    /// request that actually carries an `If-Match` header.
+    pub(crate) fn check(
+        &self,
+        last_modified: Option<LastModified>,
+        etag: &ETag,
+        strong_etag: impl FnOnce() -> ETag,
+    ) -> ConditionalBody {
+        // `If-Match` takes precedence over everything else.
+        if let Some(if_match) = &self.if_match {
+            if !if_match.precondition_passes(&strong_etag()) {
+                return ConditionalBody::NoBody(precondition_failed(etag, last_modified));
+            }
+        } else if let Some(since) = &self.if_unmodified_since {
+            let unmodified = last_modified
+                .map(|time| since.precondition_passes(time.into()))
+                .unwrap_or(false);
+            if !unmodified {
+                return ConditionalBody::NoBody(precondition_failed(etag, last_modified));
+            }
+        }
+
+        // `If-None-Match` takes precedence over `If-Modified-Since` per RFC 7232.
+        if let Some(if_none_match) = &self.if_none_match {
+            if !if_none_match.precondition_passes(etag) {
+                return ConditionalBody::NoBody(not_modified(etag, last_modified));
+            }
+        } else if let Some(since) = &self.if_modified_since {
+            let not_modified_ = last_modified
+                .map(|time| !since.is_modified(time.into()))
+                .unwrap_or(false);
+            if not_modified_ {
+                return ConditionalBody::NoBody(not_modified(etag, last_modified));
+            }
+        }
+
+        // A stale `If-Range` validator means the client's cached range no longer applies,
+        // so fall back to a full `200` instead of serving a now-bogus partial response.
+        if let Some(if_range) = &self.if_range {
+            let last_modified_time = last_modified.map(SystemTime::from);
+            if if_range.is_modified(Some(etag), last_modified_time.as_ref()) {
+                return ConditionalBody::WithBody(None);
+            }
+        }
+
+        ConditionalBody::WithBody(self.range.clone())
+    }
+}
+
+fn precondition_failed(etag: &ETag, last_modified: Option<LastModified>) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::PRECONDITION_FAILED;
+    resp.headers_mut().typed_insert(etag.clone());
+    if let Some(last_modified) = last_modified {
+        resp.headers_mut().typed_insert(last_modified);
+    }
+    resp
+}
+
+fn not_modified(etag: &ETag, last_modified: Option<LastModified>) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::NOT_MODIFIED;
+    resp.headers_mut().typed_insert(etag.clone());
+    if let Some(last_modified) = last_modified {
+        resp.headers_mut().typed_insert(last_modified);
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with(entries: &[(hyper::header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.insert(name.clone(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn etag(s: &str) -> ETag {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn if_range_with_a_fresh_etag_honors_the_range() {
+        let headers = headers_with(&[
+            (hyper::header::IF_RANGE, "\"v1\""),
+            (hyper::header::RANGE, "bytes=0-9"),
+        ]);
+        let conditionals = ConditionalHeaders::new(&headers);
+
+        match conditionals.check(None, &etag("\"v1\""), || etag("\"v1\"")) {
+            ConditionalBody::WithBody(range) => assert!(range.is_some()),
+            ConditionalBody::NoBody(_) => panic!("expected a range to be honored"),
+        }
+    }
+
+    #[test]
+    fn if_range_with_a_stale_etag_falls_back_to_the_full_body() {
+        let headers = headers_with(&[
+            (hyper::header::IF_RANGE, "\"v1\""),
+            (hyper::header::RANGE, "bytes=0-9"),
+        ]);
+        let conditionals = ConditionalHeaders::new(&headers);
+
+        // The resource's current etag (`v2`) differs from the `If-Range` validator
+        // (`v1`), so the range must be dropped in favor of a full `200` response.
+        match conditionals.check(None, &etag("\"v2\""), || etag("\"v2\"")) {
+            ConditionalBody::WithBody(range) => assert!(range.is_none()),
+            ConditionalBody::NoBody(_) => panic!("expected a full body, not a non-body response"),
+        }
+    }
+
+    #[test]
+    fn if_match_is_satisfied_by_the_strong_etag_not_the_weak_one() {
+        let headers = headers_with(&[(hyper::header::IF_MATCH, "\"abc\"")]);
+        let conditionals = ConditionalHeaders::new(&headers);
+
+        // The weak identity etag never satisfies `If-Match`; only the strong,
+        // content-based validator can.
+        match conditionals.check(None, &etag("W/\"abc\""), || etag("\"abc\"")) {
+            ConditionalBody::WithBody(_) => (),
+            ConditionalBody::NoBody(resp) => {
+                panic!("expected the strong etag to satisfy If-Match, got {:?}", resp.status())
+            }
+        }
+    }
+
+    #[test]
+    fn if_match_rejects_a_mismatched_strong_etag() {
+        let headers = headers_with(&[(hyper::header::IF_MATCH, "\"abc\"")]);
+        let conditionals = ConditionalHeaders::new(&headers);
+
+        match conditionals.check(None, &etag("W/\"abc\""), || etag("\"def\"")) {
+            ConditionalBody::NoBody(resp) => {
+                assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED)
+            }
+            ConditionalBody::WithBody(_) => panic!("expected a 412"),
+        }
+    }
+}
@@ -0,0 +1,329 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// This file is part of Static Web Server.
+// See https://static-web-server.net/ for more information
+// Copyright (C) 2019-present Jose Quintana <joseluisq.net>
+
+//! It provides an optional disk-backed second tier for the in-memory files cache.
+//!
+//! Entries that overflow the memory `SieveCache` (because they were evicted, or
+//! because they're larger than `file_max_size`) are persisted here, keyed by a
+//! hash of the original cache key, so a later lookup can be served without
+//! going back to the origin filesystem.
+
+use bytes::BytesMut;
+use headers::{ContentType, ETag, LastModified};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::mem_cache::MemFile;
+
+/// Computes the on-disk path for a cache `key` under `dir`, keyed by its hash.
+///
+/// The hash alone isn't collision-proof, so the original `key` is also stored
+/// in the entry's header and re-checked on [`read`].
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    dir.join(format!("{:016x}.cache", hasher.finish()))
+}
+
+/// An on-disk entry opened for writing, positioned right after its header so
+/// the body can be appended as it becomes available (e.g. while streaming).
+#[derive(Debug)]
+pub(crate) struct DiskEntryWriter {
+    dir: PathBuf,
+    key: String,
+    tmp_path: PathBuf,
+    file: fs::File,
+}
+
+impl DiskEntryWriter {
+    /// Appends a chunk of body bytes to the entry.
+    pub(crate) fn write_all(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk)
+    }
+
+    /// Publishes the entry atomically under its final path.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        drop(self.file);
+        fs::rename(&self.tmp_path, entry_path(&self.dir, &self.key))
+    }
+}
+
+/// Opens a new disk tier entry for `key` and writes its header (everything but
+/// the body), returning a writer the caller appends body bytes to.
+///
+/// `data_len` is the resource's declared total size and is trusted as-is;
+/// the caller is responsible for writing exactly that many body bytes.
+pub(crate) fn create(
+    dir: &Path,
+    key: &str,
+    content_type: &ContentType,
+    etag: &ETag,
+    last_modified: Option<LastModified>,
+    buf_size: usize,
+    data_len: u64,
+    ttl: u64,
+) -> io::Result<DiskEntryWriter> {
+    fs::create_dir_all(dir)?;
+
+    let last_modified_secs = last_modified.map(|lm| {
+        SystemTime::from(lm)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    });
+    let expires_at_secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        + ttl;
+
+    let mut header = Vec::with_capacity(128);
+    write_str(&mut header, key);
+    write_str(&mut header, &content_type.to_string());
+    write_str(&mut header, &etag.to_string());
+    write_opt_u64(&mut header, last_modified_secs);
+    header.extend_from_slice(&expires_at_secs.to_le_bytes());
+    header.extend_from_slice(&(buf_size as u64).to_le_bytes());
+    header.extend_from_slice(&data_len.to_le_bytes());
+
+    let tmp_path = entry_path(dir, key).with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(&header)?;
+
+    Ok(DiskEntryWriter {
+        dir: dir.to_path_buf(),
+        key: key.to_owned(),
+        tmp_path,
+        file,
+    })
+}
+
+/// Persists the already fully-buffered `file` to the disk tier under `key` in
+/// one shot, expiring after `ttl` seconds.
+pub(crate) fn write(dir: &Path, key: &str, file: &MemFile, ttl: u64) -> io::Result<()> {
+    let mut writer = create(
+        dir,
+        key,
+        &file.content_type,
+        &file.etag,
+        file.last_modified,
+        file.buf_size,
+        file.data.len() as u64,
+        ttl,
+    )?;
+    writer.write_all(&file.data)?;
+    writer.finish()
+}
+
+/// Reads the disk tier entry for `key`, if present and not expired.
+/// Deletes the file in place when it has expired. An entry whose stored key
+/// doesn't match `key` (a hash collision in [`entry_path`]) is ignored and
+/// left on disk.
+pub(crate) fn read(dir: &Path, key: &str) -> Option<MemFile> {
+    let path = entry_path(dir, key);
+    let buf = fs::read(&path).ok()?;
+    let mut cursor = 0usize;
+
+    let stored_key = read_str(&buf, &mut cursor)?;
+    if stored_key != key {
+        tracing::warn!(
+            "disk cache key hash collision for `{}` (found `{}`); ignoring stale entry",
+            key,
+            stored_key
+        );
+        return None;
+    }
+
+    let content_type = read_str(&buf, &mut cursor)?;
+    let etag = read_str(&buf, &mut cursor)?;
+    let last_modified_secs = read_opt_u64(&buf, &mut cursor)?;
+    let expires_at_secs = read_u64(&buf, &mut cursor)?;
+    let buf_size = read_u64(&buf, &mut cursor)? as usize;
+    let data_len = read_u64(&buf, &mut cursor)? as usize;
+    let data = buf.get(cursor..cursor + data_len)?;
+
+    let now_secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if expires_at_secs <= now_secs {
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+
+    Some(MemFile {
+        data: BytesMut::from(data),
+        buf_size,
+        content_type: content_type.parse::<ContentType>().ok()?,
+        etag: etag.parse::<ETag>().ok()?,
+        last_modified: last_modified_secs.map(|secs| {
+            LastModified::from(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        }),
+        expiration: Instant::now() + Duration::from_secs(expires_at_secs - now_secs),
+        source_path: None,
+        compressed: std::collections::HashMap::new(),
+    })
+}
+
+/// Removes the disk tier entry for `key`, if any.
+pub(crate) fn remove(dir: &Path, key: &str) {
+    let _ = fs::remove_file(entry_path(dir, key));
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn write_opt_u64(buf: &mut Vec<u8>, v: Option<u64>) {
+    match v {
+        Some(v) => {
+            buf.push(1);
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        None => buf.push(0),
+    }
+}
+
+fn read_u64(buf: &[u8], cursor: &mut usize) -> Option<u64> {
+    let bytes = buf.get(*cursor..*cursor + 8)?;
+    *cursor += 8;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_opt_u64(buf: &[u8], cursor: &mut usize) -> Option<Option<u64>> {
+    let flag = *buf.get(*cursor)?;
+    *cursor += 1;
+    if flag == 0 {
+        return Some(None);
+    }
+    read_u64(buf, cursor).map(Some)
+}
+
+fn read_str(buf: &[u8], cursor: &mut usize) -> Option<String> {
+    let len = u32::from_le_bytes(buf.get(*cursor..*cursor + 4)?.try_into().ok()?) as usize;
+    *cursor += 4;
+    let s = buf.get(*cursor..*cursor + len)?;
+    *cursor += len;
+    String::from_utf8(s.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use headers::HeaderMapExt;
+
+    fn content_type() -> ContentType {
+        ContentType::text()
+    }
+
+    fn etag() -> ETag {
+        "W/\"1a-0\"".parse().unwrap()
+    }
+
+    #[test]
+    fn round_trips_an_entry() {
+        let dir = std::env::temp_dir().join(format!(
+            "swsrv-disk-cache-test-{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        write(
+            &dir,
+            "/round-trip.txt",
+            &MemFile {
+                data: BytesMut::from(&b"hello world"[..]),
+                buf_size: 4096,
+                content_type: content_type(),
+                etag: etag(),
+                last_modified: None,
+                expiration: Instant::now() + Duration::from_secs(60),
+                source_path: None,
+                compressed: Default::default(),
+            },
+            60,
+        )
+        .unwrap();
+
+        let file = read(&dir, "/round-trip.txt").expect("entry should be readable");
+        assert_eq!(&file.data[..], b"hello world");
+        assert_eq!(file.buf_size, 4096);
+
+        let mut headers = headers::HeaderMap::new();
+        headers.typed_insert(file.content_type.clone());
+        assert_eq!(headers.typed_get::<ContentType>(), Some(content_type()));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_a_hash_collision() {
+        let dir = std::env::temp_dir().join(format!(
+            "swsrv-disk-cache-test-collision-{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        write(
+            &dir,
+            "/a.txt",
+            &MemFile {
+                data: BytesMut::from(&b"from a"[..]),
+                buf_size: 4096,
+                content_type: content_type(),
+                etag: etag(),
+                last_modified: None,
+                expiration: Instant::now() + Duration::from_secs(60),
+                source_path: None,
+                compressed: Default::default(),
+            },
+            60,
+        )
+        .unwrap();
+
+        // Simulate a hash collision: place `/a.txt`'s entry at the path `/b.txt`
+        // maps to, so the header there holds `/a.txt`'s key and a lookup under
+        // `/b.txt` must be rejected rather than silently returning `/a.txt`'s bytes.
+        fs::copy(entry_path(&dir, "/a.txt"), entry_path(&dir, "/b.txt")).unwrap();
+        assert!(read(&dir, "/b.txt").is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn streamed_write_matches_one_shot_write() {
+        let dir = std::env::temp_dir().join(format!(
+            "swsrv-disk-cache-test-stream-{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        let body = b"streamed in two chunks";
+        let mut writer = create(
+            &dir,
+            "/streamed.txt",
+            &content_type(),
+            &etag(),
+            None,
+            4096,
+            body.len() as u64,
+            60,
+        )
+        .unwrap();
+        writer.write_all(&body[..10]).unwrap();
+        writer.write_all(&body[10..]).unwrap();
+        writer.finish().unwrap();
+
+        let file = read(&dir, "/streamed.txt").expect("entry should be readable");
+        assert_eq!(&file.data[..], &body[..]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// This file is part of Static Web Server.
+// See https://static-web-server.net/ for more information
+// Copyright (C) 2019-present Jose Quintana <joseluisq.net>
+
+//! It provides helpers to build file responses, such as byte-range parsing.
+//!
+
+use headers::Range;
+use std::ops::Bound;
+
+/// Maximum number of ranges accepted in a single `Range` header before it's considered
+/// unreasonable and rejected with `416 Range Not Satisfiable`.
+const MAX_RANGES: usize = 128;
+
+/// Error when the requested `Range` header can't be satisfied against the file length.
+#[derive(Debug)]
+pub(crate) struct BadRangeError;
+
+/// Resolves a single `(start, end)` byte range out of an optional `Range` header.
+///
+/// When no `Range` header is present the whole file is returned as `(0, len)`.
+pub(crate) fn bytes_range(range: Option<Range>, len: u64) -> Result<(u64, u64), BadRangeError> {
+    let range = match range {
+        Some(range) => range,
+        None => return Ok((0, len)),
+    };
+
+    let ret = range
+        .satisfiable_ranges(len)
+        .next()
+        .map(|(start, end)| {
+            let start = match start {
+                Bound::Included(s) => s,
+                Bound::Excluded(s) => s + 1,
+                Bound::Unbounded => 0,
+            };
+            let end = match end {
+                Bound::Included(e) => e + 1,
+                Bound::Excluded(e) => e,
+                Bound::Unbounded => len,
+            };
+            (start, end)
+        });
+
+    match ret {
+        Some((start, end)) if start < end && end <= len => Ok((start, end)),
+        _ => Err(BadRangeError),
+    }
+}
+
+/// Resolves every `(start, end)` byte range out of an optional `Range` header,
+/// coalescing overlapping or adjacent ranges and rejecting unreasonable requests.
+///
+/// When no `Range` header is present the whole file is returned as a single range.
+pub(crate) fn bytes_ranges(range: Option<Range>, len: u64) -> Result<Vec<(u64, u64)>, BadRangeError> {
+    let range = match range {
+        Some(range) => range,
+        None => return Ok(vec![(0, len)]),
+    };
+
+    let mut ranges: Vec<(u64, u64)> = range
+        .satisfiable_ranges(len)
+        .filter_map(|(start, end)| {
+            let start = match start {
+                Bound::Included(s) => s,
+                Bound::Excluded(s) => s + 1,
+                Bound::Unbounded => 0,
+            };
+            let end = match end {
+                Bound::Included(e) => e + 1,
+                Bound::Excluded(e) => e,
+                Bound::Unbounded => len,
+            };
+            (start < end && end <= len).then_some((start, end))
+        })
+        .collect();
+
+    if ranges.is_empty() || ranges.len() > MAX_RANGES {
+        return Err(BadRangeError);
+    }
+
+    ranges.sort_unstable_by_key(|&(start, _)| start);
+
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match coalesced.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => coalesced.push((start, end)),
+        }
+    }
+
+    let total_requested: u64 = coalesced.iter().map(|&(start, end)| end - start).sum();
+    if total_requested > len {
+        return Err(BadRangeError);
+    }
+
+    Ok(coalesced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use headers::HeaderMapExt;
+
+    fn range(header_value: &str) -> Range {
+        let mut headers = headers::HeaderMap::new();
+        headers.insert(
+            hyper::header::RANGE,
+            hyper::header::HeaderValue::from_str(header_value).unwrap(),
+        );
+        headers.typed_get::<Range>().unwrap()
+    }
+
+    #[test]
+    fn bytes_range_defaults_to_the_whole_file_without_a_range_header() {
+        assert_eq!(bytes_range(None, 100).unwrap(), (0, 100));
+    }
+
+    #[test]
+    fn bytes_range_resolves_a_single_range() {
+        assert_eq!(bytes_range(Some(range("bytes=0-9")), 100).unwrap(), (0, 10));
+    }
+
+    #[test]
+    fn bytes_range_rejects_a_range_past_the_end_of_the_file() {
+        assert!(bytes_range(Some(range("bytes=200-300")), 100).is_err());
+    }
+
+    #[test]
+    fn bytes_ranges_defaults_to_the_whole_file_without_a_range_header() {
+        assert_eq!(bytes_ranges(None, 100).unwrap(), vec![(0, 100)]);
+    }
+
+    #[test]
+    fn bytes_ranges_coalesces_overlapping_and_adjacent_ranges() {
+        // `0-9` and `5-14` overlap and get merged; `15-19` is adjacent to the
+        // merged range and also folds in; `40-49` stays separate.
+        let ranges = bytes_ranges(Some(range("bytes=0-9,5-14,15-19,40-49")), 100).unwrap();
+        assert_eq!(ranges, vec![(0, 20), (40, 50)]);
+    }
+
+    #[test]
+    fn bytes_ranges_rejects_more_than_max_ranges() {
+        let header_value = format!(
+            "bytes={}",
+            (0..(MAX_RANGES as u64 + 1))
+                .map(|i| format!("{}-{}", i * 2, i * 2))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        assert!(bytes_ranges(Some(range(&header_value)), 10_000).is_err());
+    }
+}
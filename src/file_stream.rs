@@ -6,14 +6,15 @@
 //! Module that provides file stream functionality.
 //!
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 use futures_util::Stream;
 use std::fs::Metadata;
 use std::io::Read;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use crate::mem_cache::MEM_CACHE;
+use crate::disk_cache::{self, DiskEntryWriter};
+use crate::mem_cache::{self, CACHE_OPTS, CACHE_STORE};
 use crate::Result;
 
 #[cfg(unix)]
@@ -22,41 +23,143 @@ const DEFAULT_READ_BUF_SIZE: usize = 4_096;
 #[cfg(not(unix))]
 const DEFAULT_READ_BUF_SIZE: usize = 8_192;
 
+/// Where a streamed file's body bytes are being routed.
+#[derive(Debug)]
+enum DiskSpill {
+    /// Not yet decided; resolved on the first poll.
+    Undecided,
+    /// Bytes are appended to the memory tier's `MemFile.data` as usual.
+    InMemory,
+    /// Bytes are streamed straight to an already-open disk tier entry,
+    /// because the resource's declared length is known to exceed
+    /// `file_max_size` before a single byte of it has been read.
+    ToDisk(DiskEntryWriter),
+}
+
 #[derive(Debug)]
 pub(crate) struct FileStream<T> {
     pub(crate) reader: T,
     pub(crate) buf_size: usize,
-    pub(crate) path_str: Option<String>,
+    pub(crate) file_path: Option<String>,
+    disk_spill: DiskSpill,
+}
+
+impl<T> FileStream<T> {
+    pub(crate) fn new(reader: T, buf_size: usize, file_path: Option<String>) -> Self {
+        Self {
+            reader,
+            buf_size,
+            file_path,
+            disk_spill: DiskSpill::Undecided,
+        }
+    }
 }
 
 impl<T: Read + Unpin> Stream for FileStream<T> {
     type Item = Result<Bytes>;
 
     fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut buf = BytesMut::zeroed(self.buf_size);
-        let path_str = self.path_str.to_owned();
+        let this = Pin::into_inner(self);
 
-        match Pin::into_inner(self).reader.read(&mut buf[..]) {
+        if matches!(this.disk_spill, DiskSpill::Undecided) {
+            this.disk_spill = this
+                .file_path
+                .as_deref()
+                .map(decide_disk_spill)
+                .unwrap_or(DiskSpill::InMemory);
+        }
+
+        let mut buf = BytesMut::zeroed(this.buf_size);
+        match this.reader.read(&mut buf[..]) {
             Ok(n) => {
                 if n == 0 {
-                    Poll::Ready(None)
-                } else {
-                    if let Some(s) = path_str {
-                        if let Ok(mut guard) = MEM_CACHE.lock() {
-                            if let Some(mem_file) = guard.get_mut(s.as_str()) {
-                                mem_file.bytes.put(buf.clone());
+                    if let DiskSpill::ToDisk(writer) =
+                        std::mem::replace(&mut this.disk_spill, DiskSpill::InMemory)
+                    {
+                        if let Err(err) = writer.finish() {
+                            tracing::warn!("unable to finalize disk cache entry: {:?}", err);
+                        }
+                    }
+                    return Poll::Ready(None);
+                }
+
+                buf.truncate(n);
+                match &mut this.disk_spill {
+                    DiskSpill::ToDisk(writer) => {
+                        if let Err(err) = writer.write_all(&buf) {
+                            tracing::warn!("unable to stream bytes to disk cache entry: {:?}", err);
+                        }
+                    }
+                    _ => {
+                        if let Some(path) = &this.file_path {
+                            if let Some(store) = CACHE_STORE.get() {
+                                if let Ok(mut guard) = store.lock() {
+                                    if let Some(mem_file) = guard.get_mut(path.as_str()) {
+                                        mem_file.data.extend_from_slice(&buf);
+                                    }
+                                }
                             }
                         }
                     }
-                    buf.truncate(n);
-                    Poll::Ready(Some(Ok(buf.freeze())))
                 }
+                Poll::Ready(Some(Ok(buf.freeze())))
             }
             Err(err) => Poll::Ready(Some(Err(anyhow::Error::from(err)))),
         }
     }
 }
 
+/// Decides, before a single body byte has been read, whether `path`'s entry
+/// should be streamed straight to the disk tier instead of buffered in memory.
+///
+/// This is possible because `MemFile::new` reserves `data`'s capacity for the
+/// resource's full declared length up front, so that length is already known
+/// at stream-start. A large file's body bytes are never buffered in the memory
+/// tier; only its still-empty entry (with that reserved capacity) sits there
+/// until this decision removes it.
+fn decide_disk_spill(path: &str) -> DiskSpill {
+    let (Some(store), Some(opts)) = (CACHE_STORE.get(), CACHE_OPTS.get()) else {
+        return DiskSpill::InMemory;
+    };
+    let Some(dir) = &opts.disk_dir else {
+        return DiskSpill::InMemory;
+    };
+
+    let Ok(mut guard) = store.lock() else {
+        return DiskSpill::InMemory;
+    };
+    let Some(file) = guard.get(path) else {
+        return DiskSpill::InMemory;
+    };
+
+    let declared_len = file.data.capacity() as u64;
+    if declared_len <= opts.file_max_size || declared_len > opts.disk_max_size {
+        return DiskSpill::InMemory;
+    }
+
+    let writer = disk_cache::create(
+        dir,
+        path,
+        &file.content_type,
+        &file.etag,
+        file.last_modified,
+        file.buf_size,
+        declared_len,
+        opts.disk_ttl,
+    );
+
+    match writer {
+        Ok(writer) => {
+            mem_cache::remove_from_memory(&mut guard, path);
+            DiskSpill::ToDisk(writer)
+        }
+        Err(err) => {
+            tracing::warn!("unable to create disk cache entry for `{}`: {:?}", path, err);
+            DiskSpill::InMemory
+        }
+    }
+}
+
 pub(crate) fn optimal_buf_size(metadata: &Metadata) -> usize {
     let block_size = get_block_size(metadata);
     // If file length is smaller than block size,
@@ -78,3 +181,51 @@ fn get_block_size(metadata: &Metadata) -> usize {
 fn get_block_size(_metadata: &Metadata) -> usize {
     DEFAULT_READ_BUF_SIZE
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_cache::{MemCacheOpts, MemFile};
+    use headers::ContentType;
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+
+    /// Initializes the process-global cache store once for every test in this
+    /// module: `file_max_size` of 1 MiB, a disk tier capped at 8 MiB. Another
+    /// test module may already have initialized it with the same thresholds.
+    fn ensure_store() {
+        INIT.call_once(|| {
+            let dir = std::env::temp_dir().join("swsrv-file-stream-test-disk");
+            let opts = MemCacheOpts::new(16, 1, 60).with_disk_cache(dir, 8, 60);
+            let _ = mem_cache::init_store(&opts);
+            assert!(CACHE_OPTS.get().is_some(), "cache store should be initialized");
+        });
+    }
+
+    #[test]
+    fn decides_to_spill_when_declared_length_exceeds_file_max_size() {
+        ensure_store();
+        let key = "/big-file.bin";
+        let file = MemFile::new(2 * 1024 * 1024, 4096, ContentType::octet_stream(), None, 60);
+        mem_cache::insert(key.into(), file);
+
+        match decide_disk_spill(key) {
+            DiskSpill::ToDisk(_) => (),
+            other => panic!("expected a disk spill decision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keeps_small_files_in_memory() {
+        ensure_store();
+        let key = "/small-file.bin";
+        let file = MemFile::new(16, 4096, ContentType::octet_stream(), None, 60);
+        mem_cache::insert(key.into(), file);
+
+        match decide_disk_spill(key) {
+            DiskSpill::InMemory => (),
+            other => panic!("expected the file to stay in memory, got {:?}", other),
+        }
+    }
+}
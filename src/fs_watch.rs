@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// This file is part of Static Web Server.
+// See https://static-web-server.net/ for more information
+// Copyright (C) 2019-present Jose Quintana <joseluisq.net>
+
+//! It provides filesystem-watch based cache invalidation, so an edited file
+//! doesn't keep serving stale bytes until its TTL lapses.
+//!
+//! On platforms without a supported watcher backend this module simply stays
+//! inactive and the cache falls back to TTL-only invalidation.
+
+use compact_str::CompactString;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::disk_cache;
+use crate::mem_cache::{self, CACHE_OPTS, CACHE_STORE};
+use crate::Result;
+
+/// Minimum time between processing two events for the same path, absorbing
+/// bursts of modify events some editors/filesystems emit for a single save.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+static WATCHER: OnceCell<Mutex<RecommendedWatcher>> = OnceCell::new();
+static PATH_KEYS: OnceCell<Mutex<HashMap<PathBuf, CompactString>>> = OnceCell::new();
+static LAST_EVENT: OnceCell<Mutex<HashMap<PathBuf, Instant>>> = OnceCell::new();
+
+/// Registers `root` with the OS filesystem watcher (inotify on Linux, an
+/// equivalent backend elsewhere) so cache entries under it get invalidated as
+/// soon as the underlying file is modified, removed or renamed.
+pub(crate) fn init(root: &Path) -> Result {
+    PATH_KEYS.get_or_init(|| Mutex::new(HashMap::new()));
+    LAST_EVENT.get_or_init(|| Mutex::new(HashMap::new()));
+
+    let mut watcher = match notify::recommended_watcher(handle_event) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            tracing::warn!(
+                "unable to create a filesystem watcher, falling back to TTL-only cache invalidation: {:?}",
+                err
+            );
+            return Ok(());
+        }
+    };
+
+    if let Err(err) = watcher.watch(root, RecursiveMode::Recursive) {
+        tracing::warn!(
+            "unable to watch `{}`, falling back to TTL-only cache invalidation: {:?}",
+            root.display(),
+            err
+        );
+        return Ok(());
+    }
+
+    let _ = WATCHER.set(Mutex::new(watcher));
+    tracing::debug!(
+        "filesystem-watch cache invalidation enabled for `{}`",
+        root.display()
+    );
+
+    Ok(())
+}
+
+/// Associates an absolute source `path` with its cache `key`, so a later
+/// watch event for that path can be mapped back to the entry to evict.
+pub(crate) fn track(path: PathBuf, key: CompactString) {
+    if let Some(keys) = PATH_KEYS.get() {
+        keys.lock().unwrap().insert(path, key);
+    }
+}
+
+fn handle_event(event: notify::Result<Event>) {
+    let Ok(event) = event else {
+        return;
+    };
+    if !matches!(
+        event.kind,
+        EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Create(_)
+    ) {
+        return;
+    }
+
+    for path in event.paths {
+        if is_debounced(&path) {
+            continue;
+        }
+        invalidate(&path);
+    }
+}
+
+fn is_debounced(path: &Path) -> bool {
+    let Some(last_event) = LAST_EVENT.get() else {
+        return false;
+    };
+    let mut guard = last_event.lock().unwrap();
+    let now = Instant::now();
+
+    if let Some(last) = guard.get(path) {
+        if now.duration_since(*last) < DEBOUNCE {
+            return true;
+        }
+    }
+    guard.insert(path.to_path_buf(), now);
+
+    false
+}
+
+fn invalidate(path: &Path) {
+    let Some(keys) = PATH_KEYS.get() else {
+        return;
+    };
+    let key = keys.lock().unwrap().remove(path);
+    let Some(key) = key else {
+        return;
+    };
+
+    if let Some(store) = CACHE_STORE.get() {
+        mem_cache::remove_from_memory(&mut store.lock().unwrap(), key.as_str());
+    }
+    if let Some(dir) = CACHE_OPTS.get().and_then(|opts| opts.disk_dir.as_ref()) {
+        disk_cache::remove(dir, key.as_str());
+    }
+    tracing::debug!(
+        "evicted cache entry for `{}` after a filesystem event",
+        path.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounces_repeated_events_for_the_same_path() {
+        LAST_EVENT.get_or_init(|| Mutex::new(HashMap::new()));
+        let path = PathBuf::from("/tmp/fs-watch-test-debounce.txt");
+
+        assert!(!is_debounced(&path), "first event should not be debounced");
+        assert!(is_debounced(&path), "immediate repeat should be debounced");
+    }
+
+    #[test]
+    fn does_not_debounce_distinct_paths() {
+        LAST_EVENT.get_or_init(|| Mutex::new(HashMap::new()));
+        let a = PathBuf::from("/tmp/fs-watch-test-distinct-a.txt");
+        let b = PathBuf::from("/tmp/fs-watch-test-distinct-b.txt");
+
+        assert!(!is_debounced(&a));
+        assert!(!is_debounced(&b));
+    }
+}
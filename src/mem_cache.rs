@@ -9,26 +9,83 @@
 use bytes::BytesMut;
 use compact_str::CompactString;
 use headers::{
-    AcceptRanges, ContentLength, ContentRange, ContentType, HeaderMap, HeaderMapExt, LastModified,
+    AcceptRanges, ContentLength, ContentRange, ContentType, ETag, HeaderMap, HeaderMapExt,
+    LastModified,
 };
+use hyper::header::HeaderValue;
 use hyper::{Body, Response, StatusCode};
 use once_cell::sync::OnceCell;
 use sieve_cache::SieveCache;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::conditional_headers::{ConditionalBody, ConditionalHeaders};
-use crate::file_response::{bytes_range, BadRangeError};
+use crate::disk_cache;
+use crate::file_response::{bytes_ranges, BadRangeError};
 use crate::file_stream::FileStream;
+use crate::fs_watch;
 use crate::Result;
 
+/// Length of the random boundary used to delimit parts in a `multipart/byteranges` response.
+const BOUNDARY_LEN: usize = 32;
+
+/// Fixed per-entry overhead estimate (headers, `CompactString` key, bookkeeping)
+/// added on top of each entry's raw `data` capacity when accounting cache memory.
+const ENTRY_OVERHEAD_BYTES: u64 = 128;
+
+/// Running total of bytes currently held by the memory tier, kept in sync on
+/// every insert, eviction and removal so `cache_report` never has to walk the cache.
+static CACHE_BYTES: AtomicU64 = AtomicU64::new(0);
+/// Number of `get` calls served from either cache tier.
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+/// Number of `get` calls that missed both cache tiers.
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// A pre-computable `Content-Encoding` a cached file's body can be stored as,
+/// alongside its identity copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentEncoding {
+    /// `Content-Encoding: gzip`.
+    Gzip,
+    /// `Content-Encoding: br`.
+    Brotli,
+    /// `Content-Encoding: zstd`.
+    Zstd,
+}
+
+impl ContentEncoding {
+    fn as_header_value(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+}
+
+/// Preference order used when multiple stored encodings satisfy `Accept-Encoding`.
+const PREFERRED_ENCODINGS: [ContentEncoding; 3] = [
+    ContentEncoding::Brotli,
+    ContentEncoding::Zstd,
+    ContentEncoding::Gzip,
+];
+
 /// Global cache that stores all files in memory.
 /// It provides eviction policy using the SIEVE algorithm and TTL (Time-to-live) support.
 pub(crate) static CACHE_STORE: OnceCell<Mutex<SieveCache<CompactString, MemFile>>> =
     OnceCell::new();
 
+/// Global copy of the cache options, kept around so other modules (e.g. the
+/// disk tier and the file stream) can consult limits without threading them through.
+pub(crate) static CACHE_OPTS: OnceCell<MemCacheOpts> = OnceCell::new();
+
 /// It defines the in-memory files cache options.
+#[derive(Clone)]
 pub struct MemCacheOpts {
     /// The maximum size of the cache entries.
     pub max_size: usize,
@@ -36,6 +93,20 @@ pub struct MemCacheOpts {
     pub file_max_size: u64,
     /// The TTL per file in seconds.
     pub file_ttl: u64,
+    /// The directory used for the optional disk-backed second tier, if any.
+    pub disk_dir: Option<PathBuf>,
+    /// The maximum size per file in bytes allowed in the disk tier.
+    pub disk_max_size: u64,
+    /// The TTL per file in seconds for the disk tier.
+    pub disk_ttl: u64,
+    /// The maximum total size in bytes the memory tier is allowed to occupy.
+    pub max_bytes: u64,
+    /// Origin root to auto-invalidate cache entries for on filesystem changes, if any.
+    pub watch_root: Option<PathBuf>,
+    /// The set of encodings to pre-compress compressible entries with on insert.
+    pub compress_encodings: Vec<ContentEncoding>,
+    /// The minimum file size in bytes worth pre-compressing.
+    pub compress_min_size: u64,
 }
 
 impl MemCacheOpts {
@@ -45,8 +116,216 @@ impl MemCacheOpts {
             max_size,
             file_max_size: 1024 * 1024 * file_max_size,
             file_ttl,
+            disk_dir: None,
+            disk_max_size: 0,
+            disk_ttl: 0,
+            max_bytes: u64::MAX,
+            watch_root: None,
+            compress_encodings: Vec::new(),
+            compress_min_size: 0,
+        }
+    }
+
+    /// Enables the disk-backed second tier, storing overflow entries under `dir`.
+    pub fn with_disk_cache(mut self, dir: PathBuf, disk_max_size: u64, disk_ttl: u64) -> Self {
+        self.disk_dir = Some(dir);
+        self.disk_max_size = 1024 * 1024 * disk_max_size;
+        self.disk_ttl = disk_ttl;
+        self
+    }
+
+    /// Caps the total memory tier footprint at `max_bytes_mb` megabytes.
+    pub fn with_max_bytes(mut self, max_bytes_mb: u64) -> Self {
+        self.max_bytes = 1024 * 1024 * max_bytes_mb;
+        self
+    }
+
+    /// Enables filesystem-watch based invalidation (`auto-invalidate`) for files under `root`.
+    pub fn with_watch(mut self, root: PathBuf) -> Self {
+        self.watch_root = Some(root);
+        self
+    }
+
+    /// Pre-computes `encodings` for compressible entries whose size is at least
+    /// `min_size_kb` kilobytes.
+    pub fn with_compression(mut self, encodings: Vec<ContentEncoding>, min_size_kb: u64) -> Self {
+        self.compress_encodings = encodings;
+        self.compress_min_size = 1024 * min_size_kb;
+        self
+    }
+}
+
+/// A point-in-time snapshot of the memory cache's footprint and effectiveness,
+/// meant to be surfaced through the server's metrics/health endpoint.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheReport {
+    /// Total bytes currently held by the memory tier (data + compressed copies + key +
+    /// per-entry overhead estimate).
+    pub total_bytes: u64,
+    /// Number of entries currently held by the memory tier.
+    pub entry_count: usize,
+    /// Configured maximum number of entries.
+    pub max_size: usize,
+    /// Configured maximum total bytes.
+    pub max_bytes: u64,
+    /// Number of `get` calls served from either cache tier.
+    pub hits: u64,
+    /// Number of `get` calls that missed both cache tiers.
+    pub misses: u64,
+}
+
+/// Reports the current memory cache footprint and hit/miss counters.
+pub fn cache_report() -> CacheReport {
+    let entry_count = CACHE_STORE
+        .get()
+        .map(|store| store.lock().unwrap().len())
+        .unwrap_or(0);
+    let opts = CACHE_OPTS.get();
+
+    CacheReport {
+        total_bytes: CACHE_BYTES.load(Ordering::Relaxed),
+        entry_count,
+        max_size: opts.map(|opts| opts.max_size).unwrap_or(0),
+        max_bytes: opts.map(|opts| opts.max_bytes).unwrap_or(u64::MAX),
+        hits: CACHE_HITS.load(Ordering::Relaxed),
+        misses: CACHE_MISSES.load(Ordering::Relaxed),
+    }
+}
+
+impl CacheReport {
+    /// Serializes this report as a small JSON object, suitable for embedding
+    /// in the server's health/metrics endpoint response body.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"total_bytes\":{},\"entry_count\":{},\"max_size\":{},\"max_bytes\":{},\"hits\":{},\"misses\":{}}}",
+            self.total_bytes, self.entry_count, self.max_size, self.max_bytes, self.hits, self.misses
+        )
+    }
+}
+
+/// Builds a `200 OK` JSON response describing the current memory cache
+/// footprint and hit/miss counters. Meant to be mounted at the server's
+/// health/metrics endpoint so operators can see live cache memory
+/// consumption, rather than only via the `trace`-level log emitted on insert.
+pub fn cache_report_response() -> Response<Body> {
+    let mut resp = Response::new(Body::from(cache_report().to_json()));
+    resp.headers_mut().typed_insert(ContentType::json());
+    resp
+}
+
+/// Removes `key` from the memory tier, if present, keeping `CACHE_BYTES` in
+/// sync so every removal site (not just eviction and TTL expiry) accounts for
+/// the bytes it frees.
+pub(crate) fn remove_from_memory(guard: &mut SieveCache<CompactString, MemFile>, key: &str) {
+    if let Some(file) = guard.get(key) {
+        CACHE_BYTES.fetch_sub(entry_size(file, key), Ordering::Relaxed);
+        guard.remove(key);
+    }
+}
+
+/// Estimates the memory footprint of a cache entry: its raw data capacity, the
+/// capacity of every pre-compressed copy, the key's length, plus a fixed
+/// overhead for headers and the `CompactString` key.
+fn entry_size(file: &MemFile, key: &str) -> u64 {
+    let compressed_bytes: u64 = file
+        .compressed
+        .values()
+        .map(|data| data.capacity() as u64)
+        .sum();
+    file.data.capacity() as u64 + compressed_bytes + key.len() as u64 + ENTRY_OVERHEAD_BYTES
+}
+
+/// Whether `content_type` is worth pre-compressing (text-like formats compress
+/// well; already-compressed formats like images or video don't).
+fn is_compressible(content_type: &ContentType) -> bool {
+    let mime: mime::Mime = content_type.clone().into();
+    match (mime.type_(), mime.subtype()) {
+        (mime::TEXT, _) => true,
+        (mime::IMAGE, mime::SVG) => true,
+        (mime::APPLICATION, sub) => {
+            matches!(sub.as_str(), "json" | "javascript" | "xml" | "wasm")
+        }
+        _ => false,
+    }
+}
+
+/// Computes a pre-compressed copy of `data` for every encoding in `encodings`,
+/// skipping any that fail to compress.
+fn compress_variants(
+    data: &[u8],
+    encodings: &[ContentEncoding],
+) -> HashMap<ContentEncoding, BytesMut> {
+    encodings
+        .iter()
+        .filter_map(|encoding| compress(data, *encoding).map(|body| (*encoding, body)))
+        .collect()
+}
+
+/// Whether the client's `Accept-Encoding` header allows `encoding`, honoring
+/// per-token `q` weights (a `q=0` entry explicitly forbids that encoding).
+///
+/// An explicit token for `encoding` always takes precedence over a `*`
+/// wildcard token, regardless of which one appears first in the header.
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    let mut explicit_q = None;
+    let mut wildcard_q = None;
+
+    for token in accept_encoding.split(',') {
+        let mut parts = token.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        if name != encoding && name != "*" {
+            continue;
+        }
+
+        let q: f32 = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse().ok())
+            .unwrap_or(1.0);
+
+        if name == encoding {
+            explicit_q = Some(q);
+        } else {
+            wildcard_q = Some(q);
         }
     }
+
+    explicit_q.or(wildcard_q).unwrap_or(0.0) > 0.0
+}
+
+/// Derives a distinct weak validator for a compressed `encoding` variant, so it
+/// never compares equal to the identity copy's `etag`.
+fn variant_etag(etag: &ETag, encoding: ContentEncoding) -> ETag {
+    let inner = etag
+        .to_string()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .to_owned();
+
+    format!("W/\"{inner}-{}\"", encoding.as_header_value())
+        .parse()
+        .unwrap_or_else(|_| etag.clone())
+}
+
+fn compress(data: &[u8], encoding: ContentEncoding) -> Option<BytesMut> {
+    use std::io::Write;
+
+    let compressed = match encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).ok()?;
+            encoder.finish().ok()?
+        }
+        ContentEncoding::Brotli => {
+            let mut out = Vec::new();
+            let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+            encoder.write_all(data).ok()?;
+            drop(encoder);
+            out
+        }
+        ContentEncoding::Zstd => zstd::stream::encode_all(data, 0).ok()?,
+    };
+
+    Some(BytesMut::from(&compressed[..]))
 }
 
 /// Make sure to initialize the in-memory cache store.
@@ -58,13 +337,161 @@ pub fn init_store(opts: &MemCacheOpts) -> Result {
     if CACHE_STORE.set(Mutex::new(cache)).is_err() {
         bail!("unable to initialize the in-memory cache store")
     }
+    if let Some(dir) = &opts.disk_dir {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            bail!(format!("unable to create the disk cache directory: {err}"))
+        }
+    }
+    if CACHE_OPTS.set(opts.clone()).is_err() {
+        bail!("unable to initialize the in-memory cache store")
+    }
+    if let Some(root) = &opts.watch_root {
+        fs_watch::init(root)?;
+    }
     tracing::debug!("the in-memory cache store was initialized successfully");
 
     Ok(())
 }
 
+/// Looks up `key` in the memory tier, falling back to the disk tier on a miss
+/// and promoting the entry back into memory when found there.
+pub(crate) fn get(key: &str) -> Option<MemFile> {
+    if let Some(store) = CACHE_STORE.get() {
+        let mut guard = store.lock().unwrap();
+        match guard.get(key) {
+            Some(file) if !file.has_expired() => {
+                CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+                return Some(file.clone());
+            }
+            Some(_) => {
+                remove_from_memory(&mut guard, key);
+            }
+            None => {}
+        }
+    }
+
+    let found = CACHE_OPTS
+        .get()
+        .and_then(|opts| opts.disk_dir.as_ref())
+        .and_then(|dir| disk_cache::read(dir, key));
+
+    match found {
+        Some(file) => {
+            CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+            promote(CompactString::new(key), file.clone());
+            Some(file)
+        }
+        None => {
+            CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Inserts `file` under `key`, evicting SIEVE victims to the disk tier as needed
+/// and routing oversized files directly to disk when a disk tier is configured.
+pub(crate) fn insert(key: CompactString, file: MemFile) {
+    let opts = CACHE_OPTS.get();
+    let too_big_for_memory = opts
+        .map(|opts| file.data.len() as u64 > opts.file_max_size)
+        .unwrap_or(false);
+
+    if too_big_for_memory {
+        // Disk-tier entries are served straight from the origin/disk bytes, so
+        // pre-computing a compressed copy here would only be thrown away.
+        if let Some(opts) = opts {
+            write_to_disk(opts, key.as_str(), &file);
+        }
+        return;
+    }
+
+    insert_into_memory(key, file, opts);
+}
+
+/// Inserts an already-buffered `file` into the memory tier unconditionally,
+/// regardless of how it compares to `file_max_size`.
+///
+/// `get()` uses this to promote a disk tier hit back into memory: that `file`
+/// is already fully buffered (it's exactly what was just read off disk), so
+/// routing it back through `insert`'s size check would just rewrite the same
+/// bytes to the same disk path and never actually promote it.
+pub(crate) fn promote(key: CompactString, file: MemFile) {
+    insert_into_memory(key, file, CACHE_OPTS.get());
+}
+
+fn insert_into_memory(key: CompactString, mut file: MemFile, opts: Option<&MemCacheOpts>) {
+    // Compression is CPU-bound; callers populating the cache from an async
+    // context should do so via `spawn_blocking` (or an equivalent worker) so
+    // this doesn't stall the executor for large compressible files.
+    if let Some(opts) = opts {
+        if file.compressed.is_empty()
+            && !opts.compress_encodings.is_empty()
+            && file.data.len() as u64 >= opts.compress_min_size
+            && is_compressible(&file.content_type)
+        {
+            file.compressed = compress_variants(&file.data, &opts.compress_encodings);
+        }
+    }
+
+    let Some(store) = CACHE_STORE.get() else {
+        return;
+    };
+    let mut guard = store.lock().unwrap();
+    // Drop any entry already stored under `key` first, so its bytes are subtracted
+    // before the replacement's are added.
+    remove_from_memory(&mut guard, key.as_str());
+    let new_size = entry_size(&file, key.as_str());
+    let max_bytes = opts.map(|opts| opts.max_bytes).unwrap_or(u64::MAX);
+
+    let mut evict_victim = |guard: &mut SieveCache<CompactString, MemFile>| match guard.evict() {
+        Some((victim_key, victim_file)) => {
+            CACHE_BYTES.fetch_sub(entry_size(&victim_file, victim_key.as_str()), Ordering::Relaxed);
+            if let Some(opts) = opts {
+                write_to_disk(opts, victim_key.as_str(), &victim_file);
+            }
+            true
+        }
+        None => false,
+    };
+
+    while guard.len() >= guard.capacity() {
+        if !evict_victim(&mut guard) {
+            break;
+        }
+    }
+    while !guard.is_empty() && CACHE_BYTES.load(Ordering::Relaxed) + new_size > max_bytes {
+        if !evict_victim(&mut guard) {
+            break;
+        }
+    }
+
+    if let (Some(path), Some(opts)) = (&file.source_path, opts) {
+        if opts.watch_root.is_some() {
+            fs_watch::track(path.clone(), key.clone());
+        }
+    }
+
+    guard.insert(key, file);
+    CACHE_BYTES.fetch_add(new_size, Ordering::Relaxed);
+    drop(guard);
+
+    tracing::trace!(report = ?cache_report(), "memory cache store updated");
+}
+
+fn write_to_disk(opts: &MemCacheOpts, key: &str, file: &MemFile) {
+    let Some(dir) = &opts.disk_dir else {
+        return;
+    };
+    if file.data.len() as u64 > opts.disk_max_size {
+        return;
+    }
+    if let Err(err) = disk_cache::write(dir, key, file, opts.disk_ttl) {
+        tracing::warn!("unable to write disk cache entry: {:?}", err);
+    }
+}
+
 /// In-memory file representation to be store in the cache.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct MemFile {
     /// Bytes of the the current file.
     pub data: BytesMut,
@@ -72,10 +499,17 @@ pub(crate) struct MemFile {
     pub buf_size: usize,
     /// `Content-Type` header for the current file.
     pub content_type: ContentType,
+    /// `ETag` header for the current file, derived from its length and modification time.
+    pub etag: ETag,
     /// `Last Modified` header for the current file.
     pub last_modified: Option<LastModified>,
     /// Expiration time (TTL) of the current file in memory.
     pub expiration: Instant,
+    /// Absolute path of the origin file this entry was read from, used by the
+    /// filesystem watcher to map a change event back to its cache key.
+    pub source_path: Option<PathBuf>,
+    /// Pre-compressed copies of `data`, computed once on insert.
+    pub compressed: HashMap<ContentEncoding, BytesMut>,
 }
 
 impl MemFile {
@@ -86,15 +520,52 @@ impl MemFile {
         last_modified: Option<LastModified>,
         file_ttl: u64,
     ) -> Self {
+        let etag = Self::derive_etag(len, last_modified);
+
         Self {
             data: BytesMut::with_capacity(len as usize),
             buf_size,
             content_type,
+            etag,
             last_modified,
             expiration: Instant::now() + Duration::new(file_ttl, 0),
+            source_path: None,
+            compressed: HashMap::new(),
         }
     }
 
+    /// Attaches the absolute origin path this entry was read from, enabling
+    /// filesystem-watch invalidation for it.
+    pub(crate) fn with_source_path(mut self, path: PathBuf) -> Self {
+        self.source_path = Some(path);
+        self
+    }
+
+    /// Derives a weak validator from the file's length and modification time,
+    /// following the common `W/"{len:x}-{mtime:x}"` scheme.
+    fn derive_etag(len: u64, last_modified: Option<LastModified>) -> ETag {
+        let mtime = last_modified
+            .map(SystemTime::from)
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|dur| dur.as_secs())
+            .unwrap_or(0);
+
+        format!("W/\"{len:x}-{mtime:x}\"")
+            .parse()
+            .expect("derived etag is always a valid header value")
+    }
+
+    /// Derives a strong, content-based validator suitable for `If-Match`'s
+    /// byte-for-byte comparison, which RFC 7232 forbids weak tags from satisfying.
+    fn strong_etag(&self) -> ETag {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.data.hash(&mut hasher);
+
+        format!("\"{:016x}\"", hasher.finish())
+            .parse()
+            .unwrap_or_else(|_| self.etag.clone())
+    }
+
     pub(crate) fn has_expired(&self) -> bool {
         Instant::now() > self.expiration
     }
@@ -103,72 +574,342 @@ impl MemFile {
         let conditionals = ConditionalHeaders::new(headers);
         let modified = self.last_modified;
 
-        match conditionals.check(modified) {
+        match conditionals.check(modified, &self.etag, || self.strong_etag()) {
             ConditionalBody::NoBody(resp) => Ok(resp),
             ConditionalBody::WithBody(range) => {
                 let buf = self.data.clone().freeze();
-                let mut len = buf.len() as u64;
-                let mut reader = std::io::Cursor::new(buf);
-                let buf_size = self.buf_size;
+                let len = buf.len() as u64;
+                let had_range = range.is_some();
 
-                bytes_range(range, len)
-                    .map(|(start, end)| {
-                        match reader.seek(SeekFrom::Start(start)) {
-                            Ok(_) => (),
-                            Err(err) => {
-                                tracing::error!("seek file from start error: {:?}", err);
-                                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                            }
-                        };
-
-                        let sub_len = end - start;
-                        let reader = reader.take(sub_len);
-                        let stream = FileStream {
-                            reader,
-                            buf_size,
-                            file_path: None,
-                        };
-                        let body = Body::wrap_stream(stream);
-                        let mut resp = Response::new(body);
-
-                        if sub_len != len {
-                            *resp.status_mut() = StatusCode::PARTIAL_CONTENT;
-                            resp.headers_mut().typed_insert(
-                                match ContentRange::bytes(start..end, len) {
-                                    Ok(range) => range,
-                                    Err(err) => {
-                                        tracing::error!("invalid content range error: {:?}", err);
-                                        let mut resp = Response::new(Body::empty());
-                                        *resp.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
-                                        resp.headers_mut()
-                                            .typed_insert(ContentRange::unsatisfied_bytes(len));
-                                        return Ok(resp);
-                                    }
-                                },
-                            );
-
-                            len = sub_len;
-                        }
-
-                        resp.headers_mut().typed_insert(ContentLength(len));
-                        resp.headers_mut().typed_insert(self.content_type.clone());
-                        resp.headers_mut().typed_insert(AcceptRanges::bytes());
-
-                        if let Some(last_modified) = modified {
-                            resp.headers_mut().typed_insert(last_modified);
-                        }
-
-                        Ok(resp)
-                    })
-                    .unwrap_or_else(|BadRangeError| {
-                        // bad byte range
+                let ranges = match bytes_ranges(range, len) {
+                    Ok(ranges) => ranges,
+                    Err(BadRangeError) => {
                         let mut resp = Response::new(Body::empty());
                         *resp.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
                         resp.headers_mut()
                             .typed_insert(ContentRange::unsatisfied_bytes(len));
-                        Ok(resp)
-                    })
+                        return Ok(resp);
+                    }
+                };
+
+                if ranges.len() > 1 {
+                    return Ok(self.multipart_response(&buf, len, &ranges, modified));
+                }
+
+                let (start, end) = ranges[0];
+
+                // Range-over-compressed is unsafe (byte offsets differ from the
+                // identity copy), so only consider compressed variants for a
+                // request that isn't restricting itself to part of the file.
+                if !had_range && start == 0 && end == len {
+                    if let Some(resp) = self.compressed_response(headers, modified) {
+                        return Ok(resp);
+                    }
+                }
+
+                let mut reader = std::io::Cursor::new(buf);
+                let buf_size = self.buf_size;
+
+                match reader.seek(SeekFrom::Start(start)) {
+                    Ok(_) => (),
+                    Err(err) => {
+                        tracing::error!("seek file from start error: {:?}", err);
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                };
+
+                let sub_len = end - start;
+                let reader = reader.take(sub_len);
+                let stream = FileStream::new(reader, buf_size, None);
+                let body = Body::wrap_stream(stream);
+                let mut resp = Response::new(body);
+                let mut resp_len = len;
+
+                if sub_len != len {
+                    *resp.status_mut() = StatusCode::PARTIAL_CONTENT;
+                    resp.headers_mut().typed_insert(
+                        match ContentRange::bytes(start..end, len) {
+                            Ok(range) => range,
+                            Err(err) => {
+                                tracing::error!("invalid content range error: {:?}", err);
+                                let mut resp = Response::new(Body::empty());
+                                *resp.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                                resp.headers_mut()
+                                    .typed_insert(ContentRange::unsatisfied_bytes(len));
+                                return Ok(resp);
+                            }
+                        },
+                    );
+
+                    resp_len = sub_len;
+                }
+
+                resp.headers_mut().typed_insert(ContentLength(resp_len));
+                resp.headers_mut().typed_insert(self.content_type.clone());
+                resp.headers_mut().typed_insert(AcceptRanges::bytes());
+                resp.headers_mut().typed_insert(self.etag.clone());
+
+                if let Some(last_modified) = modified {
+                    resp.headers_mut().typed_insert(last_modified);
+                }
+
+                Ok(resp)
             }
         }
     }
+
+    /// Picks the best pre-compressed variant for the request's `Accept-Encoding`
+    /// header, if this entry has one, and builds a `200 OK` response out of it.
+    fn compressed_response(
+        &self,
+        headers: &HeaderMap,
+        last_modified: Option<LastModified>,
+    ) -> Option<Response<Body>> {
+        if self.compressed.is_empty() {
+            return None;
+        }
+
+        let accept_encoding = headers.get(hyper::header::ACCEPT_ENCODING)?.to_str().ok()?;
+        let (encoding, data) = PREFERRED_ENCODINGS.iter().find_map(|encoding| {
+            accepts_encoding(accept_encoding, encoding.as_header_value())
+                .then(|| self.compressed.get(encoding).map(|data| (*encoding, data)))
+                .flatten()
+        })?;
+
+        let mut resp = Response::new(Body::from(data.clone().freeze()));
+        resp.headers_mut()
+            .typed_insert(ContentLength(data.len() as u64));
+        resp.headers_mut().typed_insert(self.content_type.clone());
+        resp.headers_mut().typed_insert(AcceptRanges::bytes());
+        // Each encoding carries a distinct validator: two representations of
+        // the same resource must never compare equal, or a cache keying only
+        // on ETag (and ignoring `Vary`) could serve the wrong encoding back.
+        resp.headers_mut()
+            .typed_insert(variant_etag(&self.etag, encoding));
+        resp.headers_mut().insert(
+            hyper::header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_header_value()),
+        );
+        resp.headers_mut().insert(
+            hyper::header::VARY,
+            HeaderValue::from_static("accept-encoding"),
+        );
+        if let Some(last_modified) = last_modified {
+            resp.headers_mut().typed_insert(last_modified);
+        }
+
+        Some(resp)
+    }
+
+    /// Builds a `206 Partial Content` response with a `multipart/byteranges` body
+    /// covering every requested, non-overlapping range.
+    fn multipart_response(
+        &self,
+        buf: &bytes::Bytes,
+        total_len: u64,
+        ranges: &[(u64, u64)],
+        last_modified: Option<LastModified>,
+    ) -> Response<Body> {
+        let boundary = random_boundary();
+        let body = multipart_body(&self.content_type, buf, total_len, ranges, &boundary).freeze();
+        let content_length = body.len() as u64;
+
+        let mut resp = Response::new(Body::from(body));
+        *resp.status_mut() = StatusCode::PARTIAL_CONTENT;
+        resp.headers_mut().typed_insert(ContentLength(content_length));
+        resp.headers_mut().typed_insert(AcceptRanges::bytes());
+        resp.headers_mut().typed_insert(self.etag.clone());
+        if let Some(last_modified) = last_modified {
+            resp.headers_mut().typed_insert(last_modified);
+        }
+        resp.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_str(&format!(
+                "multipart/byteranges; boundary={boundary}"
+            ))
+            .expect("boundary is always a valid header value"),
+        );
+
+        resp
+    }
+}
+
+/// Builds the `multipart/byteranges` body for `ranges` out of `buf`, delimited
+/// by `boundary`. Factored out of `MemFile::multipart_response` so the exact
+/// wire format can be asserted against a fixed boundary in tests.
+fn multipart_body(
+    content_type: &ContentType,
+    buf: &bytes::Bytes,
+    total_len: u64,
+    ranges: &[(u64, u64)],
+    boundary: &str,
+) -> BytesMut {
+    let mut body = BytesMut::new();
+
+    for &(start, end) in ranges {
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        body.extend_from_slice(format!("Content-Type: {content_type}\r\n").as_bytes());
+        body.extend_from_slice(
+            format!("Content-Range: bytes {start}-{}/{total_len}\r\n\r\n", end - 1).as_bytes(),
+        );
+        body.extend_from_slice(&buf[start as usize..end as usize]);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+    body
+}
+
+/// Generates a random alphanumeric boundary for a `multipart/byteranges` response.
+fn random_boundary() -> String {
+    use rand::Rng;
+
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(BOUNDARY_LEN)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(data: &[u8]) -> MemFile {
+        let mut file = MemFile::new(data.len() as u64, 4096, ContentType::text(), None, 60);
+        file.data.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn entry_size_accounts_for_compressed_variants() {
+        let mut f = file(b"hello world");
+        let without_compressed = entry_size(&f, "/a.txt");
+
+        f.compressed
+            .insert(ContentEncoding::Gzip, BytesMut::from(&b"x"[..]));
+        let with_compressed = entry_size(&f, "/a.txt");
+
+        assert!(with_compressed > without_compressed);
+    }
+
+    #[test]
+    fn promote_moves_a_disk_hit_back_into_memory() {
+        // CACHE_STORE/CACHE_OPTS are process-global OnceCells shared with every
+        // other test module in this test binary, so these thresholds must stay
+        // in sync with `file_stream::tests::ensure_store`'s: 1 MiB file_max_size,
+        // 8 MiB disk_max_size.
+        let dir = std::env::temp_dir().join("swsrv-file-stream-test-disk");
+        let opts = MemCacheOpts::new(16, 1, 60).with_disk_cache(dir, 8, 60);
+        let _ = init_store(&opts);
+
+        let key = "/promote-me.bin";
+        // 2 MiB: over file_max_size (1 MiB) so it can't live in memory as-is,
+        // under disk_max_size (8 MiB) so the disk tier accepts it.
+        let data = vec![0u8; 2 * 1024 * 1024];
+        let mut oversized = MemFile::new(data.len() as u64, 4096, ContentType::octet_stream(), None, 60);
+        oversized.data.extend_from_slice(&data);
+
+        // Simulate the entry having already been written to disk (e.g. by SIEVE
+        // eviction or a streamed spill) and absent from the memory tier.
+        write_to_disk(CACHE_OPTS.get().unwrap(), key, &oversized);
+
+        let promoted = get(key).expect("entry should be served from the disk tier");
+        assert_eq!(promoted.data.len(), data.len());
+
+        let store = CACHE_STORE.get().unwrap();
+        let mut guard = store.lock().unwrap();
+        assert!(
+            guard.get(key).is_some(),
+            "get() should promote a disk hit back into the memory tier, not just re-serve it"
+        );
+    }
+
+    #[test]
+    fn multipart_body_matches_the_exact_wire_format_for_two_ranges() {
+        let content_type = ContentType::text();
+        let buf = bytes::Bytes::from_static(b"0123456789");
+        let ranges = [(0u64, 3u64), (5u64, 8u64)];
+
+        let body = multipart_body(&content_type, &buf, 10, &ranges, "BOUNDARY");
+
+        let expected = format!(
+            "--BOUNDARY\r\n\
+             Content-Type: {content_type}\r\n\
+             Content-Range: bytes 0-2/10\r\n\
+             \r\n\
+             012\r\n\
+             --BOUNDARY\r\n\
+             Content-Type: {content_type}\r\n\
+             Content-Range: bytes 5-7/10\r\n\
+             \r\n\
+             567\r\n\
+             --BOUNDARY--\r\n"
+        );
+
+        assert_eq!(&body[..], expected.as_bytes());
+    }
+
+    #[test]
+    fn cache_report_response_is_json() {
+        let resp = cache_report_response();
+        assert_eq!(resp.headers().get(hyper::header::CONTENT_TYPE).unwrap(), "application/json");
+    }
+
+    #[test]
+    fn derive_etag_is_weak() {
+        let etag = MemFile::derive_etag(42, None);
+        assert!(etag.to_string().starts_with("W/"));
+    }
+
+    #[test]
+    fn derive_etag_changes_with_length() {
+        let a = MemFile::derive_etag(42, None);
+        let b = MemFile::derive_etag(43, None);
+        assert_ne!(a.to_string(), b.to_string());
+    }
+
+    #[test]
+    fn strong_etag_is_not_weak_and_tracks_content() {
+        let a = file(b"hello");
+        let b = file(b"hello world");
+
+        assert!(!a.strong_etag().to_string().starts_with("W/"));
+        assert_ne!(a.strong_etag().to_string(), b.strong_etag().to_string());
+    }
+
+    #[test]
+    fn strong_etag_is_stable_for_identical_content() {
+        let a = file(b"hello");
+        let b = file(b"hello");
+        assert_eq!(a.strong_etag().to_string(), b.strong_etag().to_string());
+    }
+
+    #[test]
+    fn is_compressible_accepts_text_and_rejects_binary() {
+        assert!(is_compressible(&ContentType::text()));
+        assert!(!is_compressible(&ContentType::octet_stream()));
+    }
+
+    #[test]
+    fn accepts_encoding_honors_q_zero() {
+        assert!(accepts_encoding("gzip, br;q=0", "gzip"));
+        assert!(!accepts_encoding("gzip, br;q=0", "br"));
+        assert!(accepts_encoding("*;q=0, gzip", "gzip"));
+    }
+
+    #[test]
+    fn accepts_encoding_rejects_unlisted_encodings() {
+        assert!(!accepts_encoding("gzip", "br"));
+    }
+
+    #[test]
+    fn variant_etag_differs_per_encoding_and_from_identity() {
+        let identity = MemFile::derive_etag(42, None);
+        let gzip = variant_etag(&identity, ContentEncoding::Gzip);
+        let brotli = variant_etag(&identity, ContentEncoding::Brotli);
+
+        assert_ne!(identity.to_string(), gzip.to_string());
+        assert_ne!(gzip.to_string(), brotli.to_string());
+    }
 }